@@ -0,0 +1,337 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{ops::Range, time::Duration};
+
+use utils::config::{Config, utils::AsKey};
+
+use crate::backend::BlobBackend;
+
+mod sigv4;
+
+// Full-jitter exponential backoff bounds, used when Garage doesn't send a
+// `Retry-After` header of its own. Mirrors `S3Store`'s backoff scheme so the
+// two backends behave the same way under load.
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+const BACKOFF_CAP: Duration = Duration::from_secs(60);
+
+/// Talks directly to a Garage cluster's K2V API instead of going through its
+/// S3-compatible gateway. Every blob lives as a single K2V item, keyed by a
+/// fixed partition (`blobs`) and a hex-encoded sort key, so key lookups stay
+/// O(1) without needing Garage's S3 translation layer.
+///
+/// Requests are authenticated with AWS SigV4 ([`sigv4`]), the same scheme
+/// Garage's S3-compatible gateway uses — its K2V API does not accept HTTP
+/// Basic auth.
+pub struct GarageStore {
+    client: reqwest::Client,
+    endpoint: String,
+    bucket: String,
+    access_key: String,
+    secret_key: String,
+    region: String,
+    max_retries: u32,
+}
+
+const K2V_PARTITION: &str = "blobs";
+
+impl GarageStore {
+    pub async fn open(config: &mut Config, prefix: impl AsKey) -> Option<Self> {
+        let prefix = prefix.as_key();
+
+        Some(GarageStore {
+            client: reqwest::Client::new(),
+            endpoint: config.value_require((&prefix, "endpoint"))?.to_string(),
+            bucket: config.value_require((&prefix, "bucket"))?.to_string(),
+            access_key: config.value_require((&prefix, "access-key"))?.to_string(),
+            secret_key: config.value_require((&prefix, "secret-key"))?.to_string(),
+            region: config
+                .value((&prefix, "region"))
+                .unwrap_or("garage")
+                .to_string(),
+            max_retries: config
+                .property_or_default((&prefix, "max-retries"), "3")
+                .unwrap_or(3),
+        })
+    }
+
+    fn item_path(&self) -> String {
+        format!("/{}/{}", self.bucket, K2V_PARTITION)
+    }
+
+    // `reqwest::Url::host_str`/`port` would also work, but the endpoint is
+    // always a plain `scheme://host[:port]` in config, so a string split
+    // avoids pulling in URL parsing just for this.
+    fn host(&self) -> &str {
+        self.endpoint
+            .split_once("://")
+            .map_or(self.endpoint.as_str(), |(_, rest)| rest)
+    }
+
+    /// Builds a SigV4-signed request. `query` must be the same pairs that
+    /// end up in the request URL — the signature covers them exactly.
+    fn signed_request(
+        &self,
+        method: reqwest::Method,
+        query: &[(&str, &str)],
+        body: &[u8],
+    ) -> reqwest::RequestBuilder {
+        let path = self.item_path();
+        let host = self.host();
+        let signed = sigv4::sign(
+            &self.access_key,
+            &self.secret_key,
+            &self.region,
+            host,
+            method.as_str(),
+            &path,
+            query,
+            body,
+        );
+
+        let canonical_query = sigv4::canonical_query_string(query);
+        let url = if canonical_query.is_empty() {
+            format!("{}{path}", self.endpoint)
+        } else {
+            format!("{}{path}?{canonical_query}", self.endpoint)
+        };
+
+        let mut builder = self
+            .client
+            .request(method, url)
+            .header("host", host)
+            .header("x-amz-date", signed.amz_date)
+            .header("x-amz-content-sha256", signed.payload_hash)
+            .header("authorization", signed.authorization);
+
+        if !body.is_empty() {
+            builder = builder.body(body.to_vec());
+        }
+
+        builder
+    }
+
+    async fn send_with_retry(
+        &self,
+        build: impl Fn() -> reqwest::RequestBuilder,
+    ) -> trc::Result<reqwest::Response> {
+        let mut retries_left = self.max_retries;
+
+        loop {
+            match build().send().await {
+                Ok(response) if is_retryable_status(response.status()) && retries_left > 0 => {
+                    let retry_after = retry_after_header(&response);
+                    backoff(self.max_retries - retries_left, retry_after).await;
+                    retries_left -= 1;
+                }
+                Ok(response) => return Ok(response),
+                Err(_) if retries_left > 0 => {
+                    backoff(self.max_retries - retries_left, None).await;
+                    retries_left -= 1;
+                }
+                Err(err) => return Err(into_error(err)),
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl BlobBackend for GarageStore {
+    async fn get_blob(&self, key: &[u8], range: Range<usize>) -> trc::Result<Option<Vec<u8>>> {
+        let sort_key = hex_encode(key);
+        let response = self
+            .send_with_retry(|| {
+                self.signed_request(reqwest::Method::GET, &[("sort_key", &sort_key)], b"")
+            })
+            .await?;
+
+        match response.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                // K2V has no native range fetch; slice the value client-side.
+                let blob = response.bytes().await.map_err(into_error)?;
+                let start = range.start.min(blob.len());
+                let end = range.end.min(blob.len()).max(start);
+                Ok(Some(blob[start..end].to_vec()))
+            }
+            status => Err(trc::StoreEvent::S3Error
+                .reason("Garage K2V request failed")
+                .ctx(trc::Key::Code, status.as_u16() as i64)),
+        }
+    }
+
+    async fn put_blob(&self, key: &[u8], data: &[u8]) -> trc::Result<()> {
+        let sort_key = hex_encode(key);
+        let response = self
+            .send_with_retry(|| {
+                self.signed_request(reqwest::Method::PUT, &[("sort_key", &sort_key)], data)
+            })
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(trc::StoreEvent::S3Error
+                .reason("Garage K2V request failed")
+                .ctx(trc::Key::Code, response.status().as_u16() as i64))
+        }
+    }
+
+    async fn delete_blob(&self, key: &[u8]) -> trc::Result<bool> {
+        let sort_key = hex_encode(key);
+        let response = self
+            .send_with_retry(|| {
+                self.signed_request(reqwest::Method::DELETE, &[("sort_key", &sort_key)], b"")
+            })
+            .await?;
+
+        match response.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(trc::StoreEvent::S3Error
+                .reason("Garage K2V request failed")
+                .ctx(trc::Key::Code, status.as_u16() as i64)),
+        }
+    }
+
+    async fn list(&self, prefix: &[u8]) -> trc::Result<Vec<Vec<u8>>> {
+        let prefix_hex = hex_encode(prefix);
+        let response = self
+            .send_with_retry(|| {
+                self.signed_request(reqwest::Method::GET, &[("prefix", &prefix_hex)], b"")
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(trc::StoreEvent::S3Error
+                .reason("Garage K2V list failed")
+                .ctx(trc::Key::Code, response.status().as_u16() as i64));
+        }
+
+        let sort_keys: Vec<String> = response.json().await.map_err(into_error)?;
+        Ok(sort_keys
+            .into_iter()
+            .filter_map(|sort_key| hex_decode(&sort_key))
+            .collect())
+    }
+}
+
+fn into_error(err: impl std::fmt::Display) -> trc::Error {
+    trc::StoreEvent::S3Error.reason(err)
+}
+
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status.as_u16() == 429 || status.is_server_error()
+}
+
+// Full jitter: sleep(rand(0, min(cap, base * 2^attempt))). Spreads retries
+// out evenly instead of a fixed `1 << n` delay that synchronizes retry
+// storms across nodes.
+fn full_jitter_backoff(attempt: u32) -> Duration {
+    let max = BACKOFF_BASE
+        .saturating_mul(1 << attempt.min(6))
+        .min(BACKOFF_CAP);
+    max.mul_f64(rand::random::<f64>())
+}
+
+async fn backoff(attempt: u32, retry_after: Option<Duration>) {
+    tokio::time::sleep(retry_after.unwrap_or_else(|| full_jitter_backoff(attempt))).await;
+}
+
+fn retry_after_header(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    hex.as_bytes()
+        .chunks(2)
+        .map(|chunk| u8::from_str_radix(std::str::from_utf8(chunk).ok()?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[test]
+    fn hex_round_trip() {
+        for bytes in [&b""[..], &[0u8, 1, 2, 0xff], b"sort-key-bytes"] {
+            assert_eq!(hex_decode(&hex_encode(bytes)).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn hex_decode_rejects_odd_length_and_non_hex_input() {
+        assert_eq!(hex_decode("abc"), None);
+        assert_eq!(hex_decode("zz"), None);
+    }
+
+    fn test_store(endpoint: String) -> GarageStore {
+        GarageStore {
+            client: reqwest::Client::new(),
+            endpoint,
+            bucket: "test-bucket".to_string(),
+            access_key: "test-access".to_string(),
+            secret_key: "test-secret".to_string(),
+            region: "garage".to_string(),
+            max_retries: 0,
+        }
+    }
+
+    // Exercises the request-building/signing path end-to-end against a
+    // minimal hand-rolled HTTP server, since there's no real Garage cluster
+    // available in tests. Mainly guards against the request shape regressing
+    // (signed headers present, body forwarded) rather than validating
+    // SigV4 against a real implementation.
+    #[tokio::test]
+    async fn put_blob_sends_a_signed_request_and_reports_success() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 8192];
+            let n = socket.read(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..n]).to_string();
+
+            let response = if request.starts_with("PUT ")
+                && request.contains("authorization: AWS4-HMAC-SHA256 Credential=test-access/")
+                && request.contains("x-amz-date:")
+                && request.ends_with("payload")
+            {
+                "HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+            } else {
+                "HTTP/1.1 400 Bad Request\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+            };
+            socket.write_all(response.as_bytes()).await.unwrap();
+            request
+        });
+
+        let store = test_store(format!("http://{addr}"));
+        store.put_blob(b"\x01\x02", b"payload").await.unwrap();
+
+        let request = server.await.unwrap();
+        assert!(
+            request.starts_with("PUT "),
+            "unexpected request: {request}"
+        );
+    }
+}
@@ -0,0 +1,56 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::{collections::HashMap, ops::Range, sync::Mutex};
+
+use crate::backend::BlobBackend;
+
+/// A non-persistent blob backend backed by a plain hash map, used in tests
+/// and anywhere a real object store would be overkill.
+#[derive(Default)]
+pub struct MemoryStore {
+    blobs: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl MemoryStore {
+    pub fn open() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl BlobBackend for MemoryStore {
+    async fn get_blob(&self, key: &[u8], range: Range<usize>) -> trc::Result<Option<Vec<u8>>> {
+        Ok(self.blobs.lock().unwrap().get(key).map(|blob| {
+            let start = range.start.min(blob.len());
+            let end = range.end.min(blob.len()).max(start);
+            blob[start..end].to_vec()
+        }))
+    }
+
+    async fn put_blob(&self, key: &[u8], data: &[u8]) -> trc::Result<()> {
+        self.blobs
+            .lock()
+            .unwrap()
+            .insert(key.to_vec(), data.to_vec());
+        Ok(())
+    }
+
+    async fn delete_blob(&self, key: &[u8]) -> trc::Result<bool> {
+        Ok(self.blobs.lock().unwrap().remove(key).is_some())
+    }
+
+    async fn list(&self, prefix: &[u8]) -> trc::Result<Vec<Vec<u8>>> {
+        Ok(self
+            .blobs
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+}
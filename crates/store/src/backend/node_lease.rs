@@ -0,0 +1,88 @@
+/*
+ * SPDX-FileCopyrightText: 2020 Stalwart Labs LLC <hello@stalw.art>
+ *
+ * SPDX-License-Identifier: AGPL-3.0-only OR LicenseRef-SEL
+ */
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use utils::snowflake::NodeIdStore;
+
+use crate::backend::BlobBackend;
+
+// How long to wait before re-reading a lease we just wrote, to catch a
+// competing writer that raced us for the same free slot. Randomized so two
+// racing nodes don't both confirm at the same instant.
+const CLAIM_CONFIRM_JITTER: Duration = Duration::from_millis(250);
+
+/// A [`NodeIdStore`] backed by any [`BlobBackend`], so a deployment that
+/// already has an S3/Garage/memory store configured doesn't need a separate
+/// service just to coordinate Snowflake node ids.
+///
+/// Each slot is a blob at `node-lease/{node_id}` holding `{host_id}\t{expiry
+/// unix millis}`. Claiming is read-then-write rather than a true atomic
+/// compare-and-swap, since `BlobBackend` doesn't expose one, so two nodes
+/// racing for the same free slot in the same instant can both write. To catch
+/// that before either side mints an id under the contested node id, a claim
+/// re-reads the slot after [`CLAIM_CONFIRM_JITTER`] and backs off if it's no
+/// longer ours — cheap insurance, not a real compare-and-swap; a loser who
+/// wins the final re-read race is still only caught at the next renewal.
+pub struct BlobNodeIdStore<B> {
+    backend: B,
+}
+
+impl<B: BlobBackend> BlobNodeIdStore<B> {
+    pub fn new(backend: B) -> Self {
+        Self { backend }
+    }
+}
+
+#[async_trait::async_trait]
+impl<B: BlobBackend> NodeIdStore for BlobNodeIdStore<B> {
+    async fn try_claim(&self, node_id: u64, host_id: &str, ttl: Duration) -> trc::Result<bool> {
+        let key = lease_key(node_id);
+
+        if let Some(existing) = self.backend.get_blob(&key, 0..usize::MAX).await? {
+            if let Some((existing_host, expiry)) = parse_lease(&existing) {
+                if existing_host != host_id && expiry > now_millis() {
+                    return Ok(false);
+                }
+            }
+        }
+
+        let expiry = now_millis() + ttl.as_millis();
+        self.backend
+            .put_blob(&key, format!("{host_id}\t{expiry}").as_bytes())
+            .await?;
+
+        // Double-check: a competing claim for the same free slot could have
+        // landed right after ours. Re-read past a jitter window and yield if
+        // someone else now holds it, instead of only finding out at the next
+        // renewal (by which time ids may already have been minted).
+        tokio::time::sleep(CLAIM_CONFIRM_JITTER.mul_f64(rand::random::<f64>())).await;
+        match self.backend.get_blob(&key, 0..usize::MAX).await? {
+            Some(confirmed) => match parse_lease(&confirmed) {
+                Some((confirmed_host, _)) => Ok(confirmed_host == host_id),
+                None => Ok(false),
+            },
+            None => Ok(false),
+        }
+    }
+}
+
+fn lease_key(node_id: u64) -> Vec<u8> {
+    format!("node-lease/{node_id}").into_bytes()
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default()
+}
+
+fn parse_lease(raw: &[u8]) -> Option<(&str, u128)> {
+    let text = std::str::from_utf8(raw).ok()?;
+    let (host, expiry) = text.split_once('\t')?;
+    Some((host, expiry.parse().ok()?))
+}
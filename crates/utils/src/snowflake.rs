@@ -5,15 +5,43 @@
  */
 
 use std::{
-    sync::atomic::{AtomicU64, Ordering},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
     time::{Duration, SystemTime},
 };
 
-#[derive(Debug)]
 pub struct SnowflakeIdGenerator {
     epoch: SystemTime,
     node_id: u64,
-    sequence: AtomicU64,
+    // Packs the last-seen millisecond (high bits) and the sequence minted
+    // within it (low `SEQUENCE_LEN` bits) so both can be updated atomically.
+    // Shared (not duplicated) across clones: two clones minting IDs
+    // concurrently must observe each other's sequence numbers, or both can
+    // independently mint sequence 0 in the same millisecond and collide.
+    state: Arc<AtomicU64>,
+    // Keeps a leased node id's renewal task alive for as long as any clone
+    // of this generator is, and aborts it once the last one is dropped, so a
+    // discarded generator doesn't keep renewing a lease nobody uses anymore.
+    renewal: Option<Arc<RenewalGuard>>,
+}
+
+impl std::fmt::Debug for SnowflakeIdGenerator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SnowflakeIdGenerator")
+            .field("epoch", &self.epoch)
+            .field("node_id", &self.node_id)
+            .finish()
+    }
+}
+
+struct RenewalGuard(tokio::task::JoinHandle<()>);
+
+impl Drop for RenewalGuard {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
 }
 
 const SEQUENCE_LEN: u64 = 12;
@@ -25,6 +53,11 @@ const NODE_ID_MASK: u64 = (1 << NODE_ID_LEN) - 1;
 const DEFAULT_EPOCH: u64 = 1632280000; // 52 years after UNIX_EPOCH
 //const DEFAULT_EPOCH_MS: u128 = (DEFAULT_EPOCH as u128) * 1000; // 52 years after UNIX_EPOCH in milliseconds
 
+// How long a claimed node id is reserved for before it is considered free
+// for another node to take, and how often the owning node renews it.
+const NODE_ID_LEASE_TTL: Duration = Duration::from_secs(60);
+const NODE_ID_RENEW_INTERVAL: Duration = Duration::from_secs(20);
+
 /*
 
 ID characteristics:
@@ -35,11 +68,81 @@ ID characteristics:
 
 */
 
+/// Backing store used to coordinate node id allocation across a cluster, so
+/// that nodes don't have to rely on `rand` drawing 512 distinct values.
+#[async_trait::async_trait]
+pub trait NodeIdStore: Send + Sync {
+    /// Attempts to claim `node_id` for `host_id`. Succeeds if the slot is
+    /// unclaimed, already held by `host_id`, or its lease has expired.
+    async fn try_claim(&self, node_id: u64, host_id: &str, ttl: Duration) -> trc::Result<bool>;
+}
+
 impl SnowflakeIdGenerator {
     pub fn new() -> Self {
         Self::with_node_id(rand::random::<u64>())
     }
 
+    /// Acquires a node id by leasing a unique slot in `0..=NODE_ID_MASK` from
+    /// `store`, renewing it for as long as the generator or any clone of it
+    /// is alive (renewal stops if the lease is lost), and falling back to a
+    /// random id when no store is configured, every slot is already leased
+    /// by another node, or the store errors on every attempt.
+    pub async fn with_leased_node_id(
+        store: Option<Arc<dyn NodeIdStore>>,
+        host_id: impl Into<String>,
+    ) -> Self {
+        let Some(store) = store else {
+            return Self::new();
+        };
+        let host_id = host_id.into();
+
+        for node_id in 0..=NODE_ID_MASK {
+            match store.try_claim(node_id, &host_id, NODE_ID_LEASE_TTL).await {
+                Ok(true) => {
+                    let renewal = {
+                        let store = store.clone();
+                        let host_id = host_id.clone();
+                        tokio::spawn(async move {
+                            let mut interval = tokio::time::interval(NODE_ID_RENEW_INTERVAL);
+                            interval.tick().await;
+                            loop {
+                                interval.tick().await;
+                                if !renew_with_retry(&store, node_id, &host_id).await {
+                                    // Lease genuinely lost (claimed by someone
+                                    // else, or the store kept erroring past
+                                    // our retry budget): stop renewing rather
+                                    // than spin forever on a dead lease.
+                                    break;
+                                }
+                            }
+                        })
+                    };
+
+                    let mut generator = Self::with_node_id(node_id);
+                    generator.renewal = Some(Arc::new(RenewalGuard(renewal)));
+                    return generator;
+                }
+                Ok(false) => continue,
+                Err(_) => continue,
+            }
+        }
+
+        // Every slot is leased by another node, or the store errored on
+        // every attempt: falling back to a random node id here (not just
+        // when no store is configured at all) quietly reintroduces the exact
+        // collision risk this feature exists to remove, so make it loud
+        // rather than silent. This module has no access to the host
+        // application's structured logger, so `eprintln!` is the best we can
+        // do here; callers that do should additionally alert on this.
+        eprintln!(
+            "snowflake: could not lease a node id for host {host_id:?} (all \
+             {} slots unavailable or the store kept erroring); falling back \
+             to a random node id",
+            NODE_ID_MASK + 1
+        );
+        Self::new()
+    }
+
     pub fn from_duration(period: Duration) -> Option<u64> {
         (SystemTime::UNIX_EPOCH + Duration::from_secs(DEFAULT_EPOCH))
             .elapsed()
@@ -60,7 +163,8 @@ impl SnowflakeIdGenerator {
         Self {
             epoch: SystemTime::UNIX_EPOCH + Duration::from_secs(DEFAULT_EPOCH), // 52 years after UNIX_EPOCH
             node_id,
-            sequence: 0.into(),
+            state: Arc::new(0.into()),
+            renewal: None,
         }
     }
 
@@ -77,18 +181,53 @@ impl SnowflakeIdGenerator {
         self.epoch.elapsed().is_ok()
     }
 
-    #[inline(always)]
+    // Strictly-increasing, collision-free within a millisecond: `state` holds
+    // the last-minted (timestamp, sequence) pair and is advanced with a CAS
+    // loop so concurrent callers never observe or reuse the same pair. If the
+    // sequence for the current millisecond is exhausted we wait for the next
+    // one; if the clock has moved backwards we wait for it to catch back up
+    // rather than risk minting a duplicate.
     pub fn generate(&self) -> u64 {
-        let elapsed = self
-            .epoch
-            .elapsed()
-            .map(|e| e.as_millis())
-            .unwrap_or_default() as u64;
-        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed) & SEQUENCE_MASK;
+        loop {
+            let now_ms = self
+                .epoch
+                .elapsed()
+                .map(|e| e.as_millis())
+                .unwrap_or_default() as u64;
+            let prev = self.state.load(Ordering::Acquire);
+            let prev_ms = prev >> SEQUENCE_LEN;
+            let prev_sequence = prev & SEQUENCE_MASK;
+
+            let (ms, sequence) = match now_ms.cmp(&prev_ms) {
+                std::cmp::Ordering::Greater => (now_ms, 0),
+                std::cmp::Ordering::Equal => {
+                    let sequence = (prev_sequence + 1) & SEQUENCE_MASK;
+                    if sequence == 0 {
+                        // Sequence space exhausted for this millisecond.
+                        std::hint::spin_loop();
+                        continue;
+                    }
+                    (now_ms, sequence)
+                }
+                std::cmp::Ordering::Less => {
+                    // Clock moved backwards: wait for it to catch up rather
+                    // than risk reusing a (timestamp, sequence) pair.
+                    std::hint::spin_loop();
+                    continue;
+                }
+            };
 
-        (elapsed << (SEQUENCE_LEN + NODE_ID_LEN))
-            | (sequence << NODE_ID_LEN)
-            | (self.node_id & NODE_ID_MASK)
+            let state = (ms << SEQUENCE_LEN) | sequence;
+            if self
+                .state
+                .compare_exchange_weak(prev, state, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                return (ms << (SEQUENCE_LEN + NODE_ID_LEN))
+                    | (sequence << NODE_ID_LEN)
+                    | (self.node_id & NODE_ID_MASK);
+            }
+        }
     }
 }
 
@@ -99,11 +238,78 @@ impl Default for SnowflakeIdGenerator {
 }
 
 impl Clone for SnowflakeIdGenerator {
+    // Shares `state` rather than resetting it, so two clones minting IDs at
+    // the same time still hand out distinct (timestamp, sequence) pairs.
     fn clone(&self) -> Self {
         Self {
             epoch: self.epoch,
             node_id: self.node_id,
-            sequence: 0.into(),
+            state: self.state.clone(),
+            renewal: self.renewal.clone(),
+        }
+    }
+}
+
+// How many consecutive transient (non-claim-rejecting) errors the renewal
+// loop will ride out, with jittered backoff between attempts, before giving
+// up on the lease rather than spinning forever against a store that's down.
+const NODE_ID_RENEW_MAX_RETRIES: u32 = 5;
+
+async fn renew_with_retry(store: &Arc<dyn NodeIdStore>, node_id: u64, host_id: &str) -> bool {
+    let mut attempt = 0;
+
+    loop {
+        match store.try_claim(node_id, host_id, NODE_ID_LEASE_TTL).await {
+            Ok(claimed) => return claimed,
+            Err(_) if attempt < NODE_ID_RENEW_MAX_RETRIES => {
+                let max = Duration::from_millis(200 * (1u64 << attempt.min(5)));
+                tokio::time::sleep(max.mul_f64(rand::random::<f64>())).await;
+                attempt += 1;
+            }
+            Err(_) => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn generate_is_collision_free_across_clones() {
+        let generator = SnowflakeIdGenerator::with_node_id(1);
+
+        let ids: Vec<u64> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    let generator = generator.clone();
+                    scope.spawn(move || {
+                        (0..1000).map(|_| generator.generate()).collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        let unique: HashSet<u64> = ids.iter().copied().collect();
+        assert_eq!(unique.len(), ids.len(), "generate() minted a duplicate id");
+    }
+
+    #[test]
+    fn generate_is_strictly_increasing_on_a_single_generator() {
+        let generator = SnowflakeIdGenerator::with_node_id(7);
+        let mut last = generator.generate();
+
+        for _ in 0..10_000 {
+            let next = generator.generate();
+            assert!(next > last, "ids must be strictly increasing");
+            last = next;
         }
     }
 }